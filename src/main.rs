@@ -2,7 +2,7 @@ extern crate specs;
 
 mod quadtree;
 
-use specs::{Component, DispatcherBuilder, Fetch, Join, ReadStorage,
+use specs::{Component, DispatcherBuilder, Entity, Fetch, Join, ReadStorage,
             System, VecStorage, World, WriteStorage};
 
 use quadtree::{Bounds, Quadtree, QuadtreeRef, SysUpdateQuadtree};
@@ -23,7 +23,7 @@ impl<'a> System<'a> for SysUpdatePositions {
     type SystemData = (WriteStorage<'a, Position>,
                        WriteStorage<'a, Bounds>,
                        ReadStorage<'a, Vel>,
-                       Fetch<'a, Quadtree>);
+                       Fetch<'a, Quadtree<Entity>>);
 
     fn run(&mut self, (mut pos, mut bounds, vel, quadtree): Self::SystemData) {
         for (pos, bounds, vel) in (&mut pos, &mut bounds, &vel).join() {
@@ -59,7 +59,7 @@ fn main() {
     world.register::<Bounds>();
     world.register::<QuadtreeRef>();
     // Add Quadtree as a resource
-    world.add_resource(Quadtree::new());
+    world.add_resource(Quadtree::<Entity>::new());
 
     // Build dispatcher
     let mut dispatcher = DispatcherBuilder::new()
@@ -1,7 +1,9 @@
 use specs::{Component, Entities, Entity, FetchMut, Join, ReadStorage, System,
             VecStorage, WriteStorage};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::collections::TryReserveError;
 use std::mem::swap;
-use std::ptr::null_mut;
 
 use ::{Position};
 
@@ -24,14 +26,43 @@ impl Bounds {
         bounds
     }
 
-    fn min_sq_dist(&self, target: &Position) -> f32 {
+    /// Axis-aligned box overlap test. True when the two rectangles share any
+    /// area (touching edges alone do not count).
+    fn overlaps(&self, other: &Bounds) -> bool {
+        self.pos.x < other.pos.x + other.size &&
+        other.pos.x < self.pos.x + self.size &&
+        self.pos.y < other.pos.y + other.size &&
+        other.pos.y < self.pos.y + self.size
+    }
+
+    /// Which of the four quadrants of this box fully contains `other`, using
+    /// the same right/top index scheme as `split`. Returns `None` when `other`
+    /// straddles a midline and so cannot descend past this box.
+    fn child_index(&self, other: &Bounds) -> Option<usize> {
         let half_size = self.size * 0.5;
-        let center_x = self.pos.x + half_size;
-        let corner_x = center_x + half_size * (target.x - center_x).signum();
-        let center_y = self.pos.y + half_size;
-        let corner_y = center_y + half_size * (target.y - center_y).signum();
-        let delta_x = corner_x - target.x;
-        let delta_y = corner_y - target.y;
+        let mut idx = 0;
+        let mid_x = self.pos.x + half_size;
+        if mid_x < other.pos.x {
+            idx += 1;
+        } else if mid_x < other.pos.x + other.size {
+            return None;
+        }
+        let mid_y = self.pos.y + half_size;
+        if mid_y < other.pos.y {
+            idx += 2;
+        } else if mid_y < other.pos.y + other.size {
+            return None;
+        }
+        Some(idx)
+    }
+
+    /// Squared distance from `target` to the nearest point of this box, clamped
+    /// so a target inside the box yields `0`.
+    fn min_sq_dist(&self, target: &Position) -> f32 {
+        let clamped_x = target.x.max(self.pos.x).min(self.pos.x + self.size);
+        let clamped_y = target.y.max(self.pos.y).min(self.pos.y + self.size);
+        let delta_x = clamped_x - target.x;
+        let delta_y = clamped_y - target.y;
         delta_x * delta_x + delta_y * delta_y
     }
 }
@@ -40,228 +71,777 @@ impl Component for Bounds {
     type Storage = VecStorage<Self>;
 }
 
+/// Stable index into a `Pool`.
+///
+/// A handle keeps pointing at the same node even when the backing `Vec` grows
+/// or other nodes are reclaimed, which raw pointers into a `Vec` could not.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handle(usize);
+
+/// Sentinel handle, used for the parent of the root node.
+const NONE: Handle = Handle(::std::usize::MAX);
+
+/// A flat arena of `T` with a free-list of reclaimed slots.
+///
+/// Removing an item leaves a `None` hole that the next insert reuses, so the
+/// handles of the surviving items never change.
 #[derive(Clone, Debug)]
-pub struct QuadtreeRef(*mut QuadtreeNode);
+struct Pool<T> {
+    items: Vec<Option<T>>,
+    free: Vec<usize>,
+}
+
+impl<T> Pool<T> {
+    fn new() -> Pool<T> {
+        Pool {
+            items: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, value: T) -> Handle {
+        if let Some(idx) = self.free.pop() {
+            self.items[idx] = Some(value);
+            Handle(idx)
+        } else {
+            let idx = self.items.len();
+            self.items.push(Some(value));
+            Handle(idx)
+        }
+    }
+
+    fn try_insert(&mut self, value: T) -> Result<Handle, TryReserveError> {
+        if let Some(idx) = self.free.pop() {
+            self.items[idx] = Some(value);
+            Ok(Handle(idx))
+        } else {
+            self.items.try_reserve(1)?;
+            let idx = self.items.len();
+            self.items.push(Some(value));
+            Ok(Handle(idx))
+        }
+    }
+
+    fn remove(&mut self, handle: Handle) {
+        if self.items[handle.0].take().is_some() {
+            self.free.push(handle.0);
+        }
+    }
+
+    fn get(&self, handle: Handle) -> &T {
+        self.items[handle.0].as_ref().unwrap()
+    }
 
-unsafe impl Send for QuadtreeRef {}
-unsafe impl Sync for QuadtreeRef {}
+    fn get_mut(&mut self, handle: Handle) -> &mut T {
+        self.items[handle.0].as_mut().unwrap()
+    }
+}
+
+/// Push onto `vec`, growing it through `try_reserve` so an out-of-memory
+/// condition surfaces as an error instead of aborting the process.
+fn try_push<T>(vec: &mut Vec<T>, item: T) -> Result<(), TryReserveError> {
+    if vec.len() == vec.capacity() {
+        vec.try_reserve(1)?;
+    }
+    vec.push(item);
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct QuadtreeRef(Handle);
 
 impl Component for QuadtreeRef {
     type Storage = VecStorage<Self>;
 }
 
+/// Tuning parameters for a `Quadtree`.
+///
+/// `split_threshold` is how many members a leaf holds before it subdivides,
+/// and `max_depth` caps that subdivision: once a leaf is at `max_depth` it
+/// keeps accumulating members regardless of count. This is what stops
+/// coincident entities from splitting forever.
+#[derive(Clone, Copy, Debug)]
+pub struct QuadtreeConfig {
+    pub split_threshold: usize,
+    pub max_depth: u32,
+}
+
+impl Default for QuadtreeConfig {
+    fn default() -> QuadtreeConfig {
+        QuadtreeConfig {
+            split_threshold: 4,
+            max_depth: 16,
+        }
+    }
+}
+
+/// Error returned when a `Quadtree` cannot be built with the given config.
 #[derive(Clone, Debug)]
-struct QuadtreeNode {
-    bounds: Bounds,
-    parent: *mut QuadtreeNode,
-    children: Vec<QuadtreeNode>,
-    members: Vec<(Entity, Bounds)>,
+pub enum QuadtreeBuildError {
+    /// `split_threshold` was zero, which would split on every insert.
+    ZeroThreshold,
+    /// Allocating a node or member vector failed.
+    Reserve(TryReserveError),
 }
 
-unsafe impl Send for QuadtreeNode {}
-unsafe impl Sync for QuadtreeNode {}
+impl From<TryReserveError> for QuadtreeBuildError {
+    fn from(err: TryReserveError) -> QuadtreeBuildError {
+        QuadtreeBuildError::Reserve(err)
+    }
+}
 
-impl QuadtreeNode {
-    fn find(&self, entity: &Entity) -> Option<usize> {
+/// Something that can be stored in a `Quadtree`: it carries a rectangle and a
+/// cloneable identifier. The blanket instance below makes any `(Id, Bounds)`
+/// pair a provider, so specs `Entity` payloads and plain `u32` handles work
+/// the same way.
+pub trait BoundsProvider {
+    type Id: Clone;
+
+    fn bounds(&self) -> Bounds;
+    fn id(&self) -> Self::Id;
+}
+
+impl<Id: Clone> BoundsProvider for (Id, Bounds) {
+    type Id = Id;
+
+    fn bounds(&self) -> Bounds {
+        self.1.clone()
+    }
+
+    fn id(&self) -> Id {
+        self.0.clone()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct QuadtreeNode<Id> {
+    bounds: Bounds,
+    parent: Handle,
+    depth: u32,
+    children: Vec<Handle>,
+    members: Vec<(Id, Bounds)>,
+}
+
+impl<Id: PartialEq> QuadtreeNode<Id> {
+    fn find(&self, id: &Id) -> Option<usize> {
         for (idx, v) in self.members.iter().enumerate() {
-            if &v.0 == entity {
+            if &v.0 == id {
                 return Some(idx);
             }
         }
         None
     }
+}
+
+#[derive(Clone, Debug)]
+pub struct Quadtree<Id> {
+    pool: Pool<QuadtreeNode<Id>>,
+    top: Handle,
+    config: QuadtreeConfig,
+}
+
+impl<Id: Clone + PartialEq> Quadtree<Id> {
+    pub fn new() -> Quadtree<Id> {
+        // The default config is always valid
+        Quadtree::with_config(QuadtreeConfig::default()).unwrap()
+    }
+
+    pub fn with_config(
+        config: QuadtreeConfig,
+    ) -> Result<Quadtree<Id>, QuadtreeBuildError> {
+        if config.split_threshold == 0 {
+            return Err(QuadtreeBuildError::ZeroThreshold);
+        }
+        let mut pool = Pool::new();
+        let top = pool.try_insert(QuadtreeNode {
+            bounds: Bounds {
+                pos: Position { x: 0.0, y: 0.0 },
+                size: 1.0,
+            },
+            parent: NONE,
+            depth: 0,
+            children: Vec::new(),
+            members: Vec::new(),
+        })?;
+        Ok(Quadtree {
+            pool: pool,
+            top: top,
+            config: config,
+        })
+    }
 
-    fn find_node(&self, bounds: &Bounds) -> &QuadtreeNode {
-        if !self.children.is_empty() {
-            debug_assert!(self.children.len() == 4);
-            let half_size = self.bounds.size * 0.5;
+    /// Walk down from `start` to the deepest node that fully contains `bounds`.
+    fn find_node(&self, start: Handle, bounds: &Bounds) -> Handle {
+        let node = self.pool.get(start);
+        if !node.children.is_empty() {
+            debug_assert!(node.children.len() == 4);
+            let half_size = node.bounds.size * 0.5;
             let mut idx = 0;
-            let mid_x = self.bounds.pos.x + half_size;
+            let mid_x = node.bounds.pos.x + half_size;
             // It fits on the right half
             if mid_x < bounds.pos.x {
                 idx += 1;
             // It doesn't fit on either half
             } else if mid_x < bounds.pos.x + bounds.size {
-                return self;
+                return start;
             // Else, it fits on the left half
             }
-            let mid_y = self.bounds.pos.y + half_size;
+            let mid_y = node.bounds.pos.y + half_size;
             // It fits on the top half
             if mid_y < bounds.pos.y {
                 idx += 2;
             // It doesn't fit on either half
             } else if mid_y < bounds.pos.y + bounds.size {
-                return self;
+                return start;
             // Else, it fits on the botton half
             }
-            return self.children[idx].find_node(bounds);
+            return self.find_node(node.children[idx], bounds);
         }
-        self
+        start
     }
 
-    fn find_node_mut(&mut self, bounds: &Bounds) -> &mut QuadtreeNode {
-        if !self.children.is_empty() {
-            debug_assert!(self.children.len() == 4);
-            let half_size = self.bounds.size * 0.5;
-            let mut idx = 0;
-            let mid_x = self.bounds.pos.x + half_size;
-            // It fits on the right half
-            if mid_x < bounds.pos.x {
-                idx += 1;
-            // It doesn't fit on either half
-            } else if mid_x < bounds.pos.x + bounds.size {
-                return self;
-            // Else, it fits on the left half
+    fn add(&mut self, node_handle: Handle, entity: Id, bounds: Bounds) {
+        let depth = {
+            let node = self.pool.get_mut(node_handle);
+            if node.members.len() < self.config.split_threshold {
+                node.members.push((entity, bounds));
+                return;
             }
-            let mid_y = self.bounds.pos.y + half_size;
-            // It fits on the top half
-            if mid_y < bounds.pos.y {
-                idx += 2;
-            // It doesn't fit on either half
-            } else if mid_y < bounds.pos.y + bounds.size {
-                return self;
-            // Else, it fits on the botton half
-            }
-            return self.children[idx].find_node_mut(bounds);
+            node.depth
+        };
+        // At the depth limit a leaf just keeps growing, never splits. This is
+        // what stops (nearly) coincident entities from splitting forever.
+        if depth >= self.config.max_depth {
+            self.pool.get_mut(node_handle).members.push((entity, bounds));
+            return;
         }
-        self
+        // The node doesn't have the capacity to hold the entity
+        // We have to split it
+        let node_bounds = self.pool.get(node_handle).bounds.clone();
+        let mut members = Vec::new();
+        swap(&mut members, &mut self.pool.get_mut(node_handle).members);
+        let mut children = Vec::with_capacity(4);
+        for idx in 0..4 {
+            children.push(self.pool.insert(QuadtreeNode {
+                bounds: node_bounds.split(idx),
+                parent: node_handle,
+                depth: depth + 1,
+                children: Vec::new(),
+                members: Vec::new(),
+            }));
+        }
+        self.pool.get_mut(node_handle).children = children;
+        for (old_entity, old_bounds) in members {
+            let target = self.find_node(node_handle, &old_bounds);
+            self.pool.get_mut(target).members.push((old_entity, old_bounds));
+        }
+        let target = self.find_node(node_handle, &bounds);
+        self.pool.get_mut(target).members.push((entity, bounds));
     }
 
-    pub fn add(&mut self, entity: Entity, bounds: Bounds) {
-        if self.members.len() < 4 {
-            self.members.push((entity, bounds.clone()));
-        } else {
-            // The node doesn't have the capacity to hold the entity
-            // We have to split it
-            let mut members = Vec::new();
-            swap(&mut members, &mut self.members);
-            let parent: *mut QuadtreeNode = self;
-            for idx in 0..4 {
-                self.children.push(QuadtreeNode {
-                    bounds: self.bounds.split(idx),
-                    parent: parent,
-                    children: Vec::new(),
-                    members: Vec::new(),
+    fn remove(&mut self, node_handle: Handle, entity: Id) {
+        let idx = match self.pool.get(node_handle).find(&entity) {
+            Some(idx) => idx,
+            None => return,
+        };
+        self.pool.get_mut(node_handle).members.swap_remove(idx);
+
+        // If current node becomes empty, we might have to delete nodes
+        if self.pool.get(node_handle).members.is_empty() {
+            let mut node = self.pool.get(node_handle).parent;
+            while node != NONE {
+                let all_empty = self.pool.get(node).children.iter().all(|&c| {
+                    let child = self.pool.get(c);
+                    child.children.is_empty() && child.members.is_empty()
                 });
-            }
-            for (old_entity, old_bounds) in members {
-                self.find_node_mut(&old_bounds).members
-                    .push((old_entity, old_bounds));
-            }
-            self.find_node_mut(&bounds).members.push((entity, bounds));
-        }
-    }
-
-    pub fn remove(&mut self, entity: Entity) {
-        if let Some(idx) = self.find(&entity) {
-            self.members.swap_remove(idx);
-
-            // If current node becomes empty, we might have to delete nodes
-            if self.members.is_empty() {
-                let mut node: *mut QuadtreeNode = self.parent;
-                while node != null_mut() {
-                    let node_: &mut QuadtreeNode = unsafe { &mut *node };
-                    if node_.children.iter().all(|n| {
-                        n.children.is_empty() &&
-                        n.members.is_empty()
-                    }) {
-                        node_.children.clear();
-                        node_.children.shrink_to_fit();
-                        node = node_.parent;
-                    } else {
-                        break;
+                if all_empty {
+                    let mut children = Vec::new();
+                    swap(&mut children, &mut self.pool.get_mut(node).children);
+                    for child in children {
+                        self.pool.remove(child);
                     }
+                    node = self.pool.get(node).parent;
+                } else {
+                    break;
                 }
             }
         }
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct Quadtree {
-    top: QuadtreeNode,
-}
+    // FIXME: unused?
+    pub fn _add(&mut self, entity: Id, bounds: &Bounds) {
+        let node = self.find_node(self.top, bounds);
+        if self.pool.get(node).find(&entity).is_none() {
+            self.add(node, entity, bounds.clone());
+        }
+    }
+
+    // FIXME: unused?
+    pub fn _remove(&mut self, entity: Id, bounds: &Bounds) {
+        let node = self.find_node(self.top, bounds);
+        if let Some(idx) = self.pool.get(node).find(&entity) {
+            self.pool.get_mut(node).members.swap_remove(idx);
+        }
+    }
 
-impl Quadtree {
-    pub fn new() -> Quadtree {
-        Quadtree {
-            top: QuadtreeNode {
-                bounds: Bounds {
-                    pos: Position { x: 0.0, y: 0.0 },
-                    size: 1.0,
-                },
-                parent: null_mut(),
+    /// Insert any `BoundsProvider` payload. Non-ECS callers can insert plain
+    /// `(u32, Bounds)` or custom-id pairs directly, not just specs entities.
+    pub fn insert<P: BoundsProvider<Id = Id>>(&mut self, item: P) {
+        let bounds = item.bounds();
+        let node = self.find_node(self.top, &bounds);
+        let id = item.id();
+        if self.pool.get(node).find(&id).is_none() {
+            self.add(node, id, bounds);
+        }
+    }
+
+    /// Fallible counterpart to `add`, allocating through `try_reserve` so
+    /// memory-constrained callers can degrade gracefully instead of aborting.
+    fn try_add_at(
+        &mut self,
+        node_handle: Handle,
+        entity: Id,
+        bounds: Bounds,
+    ) -> Result<(), TryReserveError> {
+        let depth = {
+            let node = self.pool.get_mut(node_handle);
+            if node.members.len() < self.config.split_threshold {
+                node.members.try_reserve(1)?;
+                node.members.push((entity, bounds));
+                return Ok(());
+            }
+            node.depth
+        };
+        if depth >= self.config.max_depth {
+            let node = self.pool.get_mut(node_handle);
+            node.members.try_reserve(1)?;
+            node.members.push((entity, bounds));
+            return Ok(());
+        }
+        let node_bounds = self.pool.get(node_handle).bounds.clone();
+        // Allocate the four children in the pool first. Until the node's own
+        // members are swapped out below the tree is still intact, so a failure
+        // here only has to reclaim the children it already inserted.
+        let mut children = Vec::new();
+        children.try_reserve(4)?;
+        for idx in 0..4 {
+            match self.pool.try_insert(QuadtreeNode {
+                bounds: node_bounds.split(idx),
+                parent: node_handle,
+                depth: depth + 1,
                 children: Vec::new(),
                 members: Vec::new(),
+            }) {
+                Ok(handle) => children.push(handle),
+                Err(err) => {
+                    for &child in &children {
+                        self.pool.remove(child);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        // Reserve the routing table before touching the node, so the one
+        // destructive step below is preceded only by fallible allocations.
+        let member_count = self.pool.get(node_handle).members.len();
+        let mut targets = Vec::new();
+        if let Err(err) = targets.try_reserve(member_count + 1) {
+            for &child in &children {
+                self.pool.remove(child);
+            }
+            return Err(err);
+        }
+        // Commit the split: the node loses its members and gains the children.
+        let handles = [children[0], children[1], children[2], children[3]];
+        let mut members = Vec::new();
+        swap(&mut members, &mut self.pool.get_mut(node_handle).members);
+        self.pool.get_mut(node_handle).children = children;
+        for (_, old_bounds) in &members {
+            targets.push(self.find_node(node_handle, old_bounds));
+        }
+        let new_target = self.find_node(node_handle, &bounds);
+        // Reserve every destination's capacity up front so the moves that
+        // follow cannot fail and strand members across a half-split node. If a
+        // reservation fails, undo the split and hand the members back.
+        for &dest in handles.iter().chain(Some(&node_handle)) {
+            let mut count = targets.iter().filter(|&&t| t == dest).count();
+            if new_target == dest {
+                count += 1;
+            }
+            if let Err(err) = self.pool.get_mut(dest).members.try_reserve(count) {
+                swap(&mut members, &mut self.pool.get_mut(node_handle).members);
+                self.pool.get_mut(node_handle).children = Vec::new();
+                for &c in &handles {
+                    self.pool.remove(c);
+                }
+                return Err(err);
             }
         }
+        for ((old_entity, old_bounds), target) in members.into_iter().zip(targets) {
+            self.pool.get_mut(target).members.push((old_entity, old_bounds));
+        }
+        self.pool.get_mut(new_target).members.push((entity, bounds));
+        Ok(())
     }
 
-    // FIXME: unused?
-    pub fn _add(&mut self, entity: Entity, bounds: &Bounds) {
-        let node = self.top.find_node_mut(bounds);
-        if node.find(&entity).is_none() {
-            node.add(entity, bounds.clone());
+    /// Add an entity using fallible allocation. Returns the `TryReserveError`
+    /// instead of panicking if a node or member vector cannot grow.
+    pub fn try_add(
+        &mut self,
+        entity: Id,
+        bounds: Bounds,
+    ) -> Result<(), TryReserveError> {
+        let node = self.find_node(self.top, &bounds);
+        if self.pool.get(node).find(&entity).is_none() {
+            self.try_add_at(node, entity, bounds)?;
         }
+        Ok(())
     }
 
-    // FIXME: unused?
-    pub fn _remove(&mut self, entity: Entity, bounds: &Bounds) {
-        let node = self.top.find_node_mut(bounds);
-        if let Some(idx) = node.find(&entity) {
-            node.members.swap_remove(idx);
+    /// Bulk-build a tree from `items`. Instead of descending root-to-leaf and
+    /// splitting once per insert, the items are bucketed by destination
+    /// quadrant in a single recursive pass: each node is partitioned into its
+    /// four children, straddlers are kept on the node, and a leaf is filled
+    /// with one reservation — so a large static scene loads in roughly linear
+    /// time. Every allocation — the working buffer and the tree vectors alike —
+    /// goes through `try_reserve`, so huge scenes fail cleanly instead of
+    /// aborting on OOM.
+    pub fn build_from<I>(
+        items: I,
+        config: QuadtreeConfig,
+    ) -> Result<Quadtree<Id>, QuadtreeBuildError>
+        where I: IntoIterator<Item = (Id, Bounds)>
+    {
+        let mut tree = Quadtree::with_config(config)?;
+        let iter = items.into_iter();
+        let (lower, _) = iter.size_hint();
+        let mut members: Vec<(Id, Bounds)> = Vec::new();
+        members.try_reserve(lower)?;
+        for item in iter {
+            try_push(&mut members, item)?;
         }
+        let top = tree.top;
+        tree.try_build_node(top, members)?;
+        Ok(tree)
+    }
+
+    /// Recursively bucket `members` into `node_handle`'s subtree. The node is
+    /// already present in the pool as an empty leaf; this partitions the
+    /// members, keeps the straddlers, and recurses into freshly allocated
+    /// children. A failure leaves the half-built tree behind, but `build_from`
+    /// owns that tree and drops it, so callers never observe the partial state.
+    fn try_build_node(
+        &mut self,
+        node_handle: Handle,
+        members: Vec<(Id, Bounds)>,
+    ) -> Result<(), TryReserveError> {
+        let (depth, node_bounds) = {
+            let node = self.pool.get(node_handle);
+            (node.depth, node.bounds.clone())
+        };
+        if members.len() <= self.config.split_threshold
+            || depth >= self.config.max_depth
+        {
+            let node = self.pool.get_mut(node_handle);
+            node.members.try_reserve(members.len())?;
+            node.members.extend(members);
+            return Ok(());
+        }
+        let mut buckets: [Vec<(Id, Bounds)>; 4] =
+            [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+        let mut here: Vec<(Id, Bounds)> = Vec::new();
+        for (entity, bounds) in members {
+            match node_bounds.child_index(&bounds) {
+                Some(idx) => try_push(&mut buckets[idx], (entity, bounds))?,
+                None => try_push(&mut here, (entity, bounds))?,
+            }
+        }
+        {
+            let node = self.pool.get_mut(node_handle);
+            node.members.try_reserve(here.len())?;
+            node.members.extend(here);
+        }
+        let mut children = Vec::new();
+        children.try_reserve(4)?;
+        for idx in 0..4 {
+            children.push(self.pool.try_insert(QuadtreeNode {
+                bounds: node_bounds.split(idx),
+                parent: node_handle,
+                depth: depth + 1,
+                children: Vec::new(),
+                members: Vec::new(),
+            })?);
+        }
+        let handles = [children[0], children[1], children[2], children[3]];
+        self.pool.get_mut(node_handle).children = children;
+        for idx in 0..4 {
+            let mut bucket = Vec::new();
+            swap(&mut bucket, &mut buckets[idx]);
+            self.try_build_node(handles[idx], bucket)?;
+        }
+        Ok(())
     }
 
     pub fn iter_with_max_dist<'a>(
         &'a self,
         target: Position,
         max_dist: f32,
-    ) ->  QuadtreeIterator<'a> {
+    ) ->  QuadtreeIterator<'a, Id> {
         QuadtreeIterator {
+            tree: self,
             target: target,
             max_sqdist: max_dist * max_dist,
-            node: &self.top,
+            node: self.top,
             prev_node: None,
             idx: 0,
         }
     }
+
+    pub fn iter_k_nearest<'a>(
+        &'a self,
+        target: Position,
+        k: usize,
+    ) -> QuadtreeKNearest<'a, Id> {
+        let mut heap = BinaryHeap::new();
+        let key = self.pool.get(self.top).bounds.min_sq_dist(&target);
+        heap.push(HeapEntry {
+            key: Reverse(OrderedF32(key)),
+            kind: EntryKind::Node(self.top),
+        });
+        QuadtreeKNearest {
+            tree: self,
+            target: target,
+            remaining: k,
+            heap: heap,
+        }
+    }
+
+    pub fn query_region<'a>(
+        &'a self,
+        area: &Bounds,
+    ) -> QuadtreeRegionIterator<'a, Id> {
+        QuadtreeRegionIterator {
+            tree: self,
+            area: area.clone(),
+            stack: vec![self.top],
+            node: None,
+            idx: 0,
+        }
+    }
+
+    /// Call `f` on every member whose `Bounds` overlaps `area`, giving mutable
+    /// access so systems can update matched entities in place.
+    pub fn for_each_in_region_mut<F>(&mut self, area: &Bounds, mut f: F)
+        where F: FnMut(&Id, &mut Bounds)
+    {
+        let mut stack = vec![self.top];
+        while let Some(handle) = stack.pop() {
+            {
+                let node = self.pool.get_mut(handle);
+                for &mut (ref entity, ref mut bounds) in
+                    node.members.iter_mut() {
+                    if area.overlaps(bounds) {
+                        f(entity, bounds);
+                    }
+                }
+            }
+            let children = self.pool.get(handle).children.clone();
+            for child in children {
+                if area.overlaps(&self.pool.get(child).bounds) {
+                    stack.push(child);
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the members overlapping a rectangle, pruning subtrees whose
+/// bounds do not intersect it. Built by `Quadtree::query_region`.
+pub struct QuadtreeRegionIterator<'a, Id: 'a> {
+    tree: &'a Quadtree<Id>,
+    area: Bounds,
+    stack: Vec<Handle>,
+    node: Option<Handle>,
+    idx: usize,
 }
 
-pub struct QuadtreeIterator<'a> {
+impl<'a, Id: Clone + PartialEq> Iterator for QuadtreeRegionIterator<'a, Id> {
+    type Item = (&'a Id, &'a Bounds);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(handle) = self.node {
+                let node = self.tree.pool.get(handle);
+                // Yield the overlapping members of the current node
+                while self.idx < node.members.len() {
+                    let &(ref entity, ref bounds) = &node.members[self.idx];
+                    self.idx += 1;
+                    if self.area.overlaps(bounds) {
+                        return Some((entity, bounds));
+                    }
+                }
+                // Then descend into the children that intersect the area
+                for &child in &node.children {
+                    if self.area.overlaps(&self.tree.pool.get(child).bounds) {
+                        self.stack.push(child);
+                    }
+                }
+                self.node = None;
+            }
+            match self.stack.pop() {
+                Some(handle) => {
+                    self.node = Some(handle);
+                    self.idx = 0;
+                }
+                None => return None,
+            }
+        }
+    }
+}
+
+/// `f32` with a total order, so it can be used as a heap key. NaN sorts as the
+/// greatest value.
+#[derive(Clone, Copy, PartialEq)]
+struct OrderedF32(f32);
+
+impl Eq for OrderedF32 {}
+
+impl PartialOrd for OrderedF32 {
+    fn partial_cmp(&self, other: &OrderedF32) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedF32 {
+    fn cmp(&self, other: &OrderedF32) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or_else(|| {
+            match (self.0.is_nan(), other.0.is_nan()) {
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                _ => Ordering::Equal,
+            }
+        })
+    }
+}
+
+enum EntryKind {
+    Node(Handle),
+    Member(Handle, usize),
+}
+
+/// A node or member keyed by its distance to the query target. Ordered by the
+/// key alone so the heap pops the closest entry first.
+struct HeapEntry {
+    key: Reverse<OrderedF32>,
+    kind: EntryKind,
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &HeapEntry) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &HeapEntry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &HeapEntry) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// Lazy best-first iterator yielding the members closest to a target, nearest
+/// first. Built by `Quadtree::iter_k_nearest`.
+pub struct QuadtreeKNearest<'a, Id: 'a> {
+    tree: &'a Quadtree<Id>,
+    target: Position,
+    remaining: usize,
+    heap: BinaryHeap<HeapEntry>,
+}
+
+impl<'a, Id: Clone + PartialEq> Iterator for QuadtreeKNearest<'a, Id> {
+    type Item = (&'a Id, &'a Bounds);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        while let Some(entry) = self.heap.pop() {
+            match entry.kind {
+                EntryKind::Node(handle) => {
+                    let node = self.tree.pool.get(handle);
+                    for (idx, &(_, ref bounds)) in node.members.iter()
+                        .enumerate() {
+                        let key = bounds.min_sq_dist(&self.target);
+                        self.heap.push(HeapEntry {
+                            key: Reverse(OrderedF32(key)),
+                            kind: EntryKind::Member(handle, idx),
+                        });
+                    }
+                    for &child in &node.children {
+                        let key = self.tree.pool.get(child).bounds
+                            .min_sq_dist(&self.target);
+                        self.heap.push(HeapEntry {
+                            key: Reverse(OrderedF32(key)),
+                            kind: EntryKind::Node(child),
+                        });
+                    }
+                }
+                EntryKind::Member(handle, idx) => {
+                    self.remaining -= 1;
+                    let &(ref ent, ref bounds) =
+                        &self.tree.pool.get(handle).members[idx];
+                    return Some((ent, bounds));
+                }
+            }
+        }
+        None
+    }
+}
+
+pub struct QuadtreeIterator<'a, Id: 'a> {
+    tree: &'a Quadtree<Id>,
     target: Position,
     max_sqdist: f32,
-    node: &'a QuadtreeNode,
-    prev_node: Option<*const QuadtreeNode>,
+    node: Handle,
+    prev_node: Option<Handle>,
     idx: usize,
 }
 
-impl<'a> Iterator for QuadtreeIterator<'a> {
-    type Item = (&'a Entity, &'a Bounds);
+impl<'a, Id: Clone + PartialEq> Iterator for QuadtreeIterator<'a, Id> {
+    type Item = (&'a Id, &'a Bounds);
 
     fn next(&mut self) -> Option<Self::Item> {
         // Try to read next item
-        let item = if let Some(item) = self.node.members.get(self.idx) {
-            item
-        // If there are no more items, move to the next node
-        } else {
+        let have_item = self.tree.pool.get(self.node).members.get(self.idx)
+            .is_some();
+        if !have_item {
+            // If there are no more items, move to the next node
             loop {
                 let mut found = false;
+                let node = self.tree.pool.get(self.node);
 
                 // Not a leaf node: examine children
-                if !self.node.children.is_empty() {
+                if !node.children.is_empty() {
                     // Find the index to resume from, if we just moved up
                     let f_idx = if let Some(prev_node) = self.prev_node {
-                        self.node.children.iter().position(|n| {
-                            n as *const QuadtreeNode == prev_node
+                        node.children.iter().position(|&n| {
+                            n == prev_node
                         }).unwrap() + 1
                     } else {
                         0
                     };
                     // Find a node that is close enough
                     for idx in f_idx..4 {
-                        let child = &self.node.children[idx];
-                        let minsqdist = child.bounds.min_sq_dist(&self.target);
+                        let child = node.children[idx];
+                        let minsqdist = self.tree.pool.get(child).bounds
+                            .min_sq_dist(&self.target);
                         if minsqdist < self.max_sqdist {
                             self.node = child;
                             self.prev_node = None;
@@ -270,7 +850,7 @@ impl<'a> Iterator for QuadtreeIterator<'a> {
                         }
                     }
                 // If a leaf node, start the iterator there
-                } else if !self.node.members.is_empty() {
+                } else if !node.members.is_empty() {
                     self.idx = 0;
                     break;
                 // If empty leaf, we keep found=false and we'll move up
@@ -278,23 +858,22 @@ impl<'a> Iterator for QuadtreeIterator<'a> {
 
                 // Didn't find a node, move up
                 if !found {
+                    let parent = self.tree.pool.get(self.node).parent;
                     // If we are done, return None
-                    if self.node.parent == null_mut() {
+                    if parent == NONE {
                         return None;
                     // Otherwise update node and set prev_node
                     } else {
-                        self.node = unsafe { &*self.node.parent };
                         self.prev_node = Some(self.node);
+                        self.node = parent;
                     }
                 }
             }
-
-            &self.node.members[self.idx]
-        };
+        }
 
         // Yield next item
         // If there are more items in the current node, yield them
-        let &(ref ent, ref bounds) = item;
+        let &(ref ent, ref bounds) = &self.tree.pool.get(self.node).members[self.idx];
         self.idx += 1;
         Some((ent, bounds))
     }
@@ -304,7 +883,7 @@ pub struct SysUpdateQuadtree;
 
 impl<'a> System<'a> for SysUpdateQuadtree {
     type SystemData = (WriteStorage<'a, QuadtreeRef>,
-                       FetchMut<'a, Quadtree>,
+                       FetchMut<'a, Quadtree<Entity>>,
                        Entities<'a>,
                        ReadStorage<'a, Bounds>);
 
@@ -312,7 +891,7 @@ impl<'a> System<'a> for SysUpdateQuadtree {
         &mut self,
         (mut refs, mut quadtree, entities, bounds): Self::SystemData
     ) {
-        let quadtree: &mut Quadtree = &mut *quadtree;
+        let quadtree: &mut Quadtree<Entity> = &mut *quadtree;
 
         for (entity, bounds) in (&*entities, &bounds).join() {
             let half_size = bounds.size * 0.5;
@@ -322,54 +901,60 @@ impl<'a> System<'a> for SysUpdateQuadtree {
 
             if let Some(quadref) = refs.get_mut(entity) {
                 // Check that it still fits
-                let node = unsafe { &mut *quadref.0 };
-                if node.bounds.pos.x < bounds.pos.x &&
+                let node_handle = quadref.0;
+                let node_bounds = quadtree.pool.get(node_handle).bounds.clone();
+                if node_bounds.pos.x < bounds.pos.x &&
                     bounds.pos.x + bounds.size <
-                        node.bounds.pos.x + node.bounds.size &&
-                    node.bounds.pos.y < bounds.pos.y &&
+                        node_bounds.pos.x + node_bounds.size &&
+                    node_bounds.pos.y < bounds.pos.y &&
                     bounds.pos.y + bounds.size <
-                        node.bounds.pos.y + node.bounds.size {
+                        node_bounds.pos.y + node_bounds.size {
                     // Check whether it could fit in one of the children
-                    if {
-                        let ptr: *const QuadtreeNode = node;
-                        let better_child = node.find_node_mut(bounds);
-                        let better_ptr: *const QuadtreeNode = better_child;
-                        if better_ptr != ptr {
-                            println!("Moving it to children node {}, {}, {}",
-                                     better_child.bounds.pos.x,
-                                     better_child.bounds.pos.y,
-                                     better_child.bounds.size);
-                            better_child.add(entity, bounds.clone());
-                            true
-                        } else {
-                            false // This is the best place for the entity
+                    let better = quadtree.find_node(node_handle, bounds);
+                    if better != node_handle {
+                        let better_bounds =
+                            quadtree.pool.get(better).bounds.clone();
+                        println!("Moving it to children node {}, {}, {}",
+                                 better_bounds.pos.x,
+                                 better_bounds.pos.y,
+                                 better_bounds.size);
+                        if let Err(err) =
+                            quadtree.try_add_at(better, entity, bounds.clone()) {
+                            println!("skipping entity {:?}: {:?}", entity, err);
+                            continue;
                         }
-                    } {
-                        // We defer the remove call after the borrow ends
-                        node.remove(entity);
+                        quadtree.remove(node_handle, entity);
                     } else {
                         println!("Still in best node {}, {}, {}",
-                                 node.bounds.pos.x,
-                                 node.bounds.pos.y,
-                                 node.bounds.size);
+                                 node_bounds.pos.x,
+                                 node_bounds.pos.y,
+                                 node_bounds.size);
                     }
                 } else {
                     // Find the new correct position for this entity
-                    node.remove(entity);
-                    let new_node = quadtree.top.find_node_mut(bounds);
+                    quadtree.remove(node_handle, entity);
+                    let new_node = quadtree.find_node(quadtree.top, bounds);
+                    let new_bounds = quadtree.pool.get(new_node).bounds.clone();
                     println!("Moving it to node {}, {}, {}",
-                             new_node.bounds.pos.x,
-                             new_node.bounds.pos.y,
-                             new_node.bounds.size);
-                    new_node.add(entity, bounds.clone());
+                             new_bounds.pos.x,
+                             new_bounds.pos.y,
+                             new_bounds.size);
+                    if let Err(err) =
+                        quadtree.try_add_at(new_node, entity, bounds.clone()) {
+                        println!("skipping entity {:?}: {:?}", entity, err);
+                    }
                 }
             // If it's not in the quadtree yet, just add it
             } else {
-                let node = quadtree.top.find_node_mut(bounds);
+                let node = quadtree.find_node(quadtree.top, bounds);
+                let node_bounds = quadtree.pool.get(node).bounds.clone();
                 println!("Not yet in quadtree, adding to node {}, {}, {}",
-                         node.bounds.pos.x, node.bounds.pos.y,
-                         node.bounds.size);
-                node.add(entity, bounds.clone());
+                         node_bounds.pos.x, node_bounds.pos.y,
+                         node_bounds.size);
+                if let Err(err) =
+                    quadtree.try_add_at(node, entity, bounds.clone()) {
+                    println!("skipping entity {:?}: {:?}", entity, err);
+                }
             }
         }
     }